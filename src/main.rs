@@ -1,12 +1,118 @@
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rand::prelude::*;
-use tokio::task::JoinSet;
-use tokio::time::{sleep, Duration};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use rand::Rng;
+use tokio::io::{self, AsyncRead, ReadBuf};
+use tokio::sync::Semaphore;
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::Duration;
+use tokio_util::io::StreamReader;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+/// Why a download attempt failed, after all retries (if any) were exhausted.
+#[derive(Debug)]
+enum DownloadError {
+    Request(reqwest::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Request(err) => write!(f, "request failed: {}", err),
+            DownloadError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(err: reqwest::Error) -> Self {
+        DownloadError::Request(err)
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(err: io::Error) -> Self {
+        DownloadError::Io(err)
+    }
+}
+
+impl DownloadError {
+    // Client errors (4xx) won't succeed on retry; transport failures and
+    // server errors (5xx) are typically transient and worth retrying.
+    fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Request(err) => !err
+                .status()
+                .is_some_and(|status| status.is_client_error()),
+            DownloadError::Io(_) => true,
+        }
+    }
+}
+
+/// The two styles a task progress bar switches between: its normal style, and
+/// the one shown while it's backing off after a failed attempt.
+#[derive(Clone)]
+struct TaskStyles {
+    normal: ProgressStyle,
+    retry: ProgressStyle,
+}
+
+/// Exposes a `JoinSet`'s results as a `Stream`, so callers can drive it with
+/// ordinary `StreamExt` combinators instead of calling `join_next()` by hand.
+struct JoinSetStream<T> {
+    set: JoinSet<T>,
+}
+
+impl<T> JoinSetStream<T> {
+    fn new(set: JoinSet<T>) -> Self {
+        Self { set }
+    }
+}
+
+impl<T: 'static> Stream for JoinSetStream<T> {
+    type Item = Result<T, JoinError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.set.poll_join_next(cx)
+    }
+}
+
+/// Wraps an `AsyncRead` and adds every byte read to a shared counter, so the
+/// stats bar can report aggregate throughput across all in-flight downloads.
+struct CountingReader<R> {
+    inner: R,
+    total_bytes: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if poll.is_ready() {
+            let read = buf.filled().len() - before;
+            self.total_bytes.fetch_add(read as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
 // This example shows a way to use indicatif with async tokio.
-// You can imagine that this simulates the progress of a file downloader.
-// I use sleep with a random duration, to let it look more realistic
+// do_stuff downloads a real file over HTTP and reports its progress in bytes,
+// so the progress bars reflect an actual transfer instead of a simulated one.
 
 // Thanks to alice for helping me with tokio::spawn https://ryhl.io
 // https://users.rust-lang.org/t/limited-concurrency-for-future-execution-tokio/87171/7
@@ -16,109 +122,316 @@ use uuid::Uuid;
 // 2. We have a progress bar for each download task (pb_task).
 // The pb_main and pb_task are both "just" progress bars,
 // which are collect inside a MultiProgress (multi_pb).
-// All progress bars use the same style (pb_style).
+// pb_main counts finished downloads, pb_task tracks bytes received for its file.
 
 // The following variables can be adjusted:
-// - ITEMS: Controls the number of total "downloads" and length of pb_main
+// - ITEMS: Controls the number of total downloads and length of pb_main
 // - MAX_CONCURRENT: Controls how many concurrent downloads are allowed
-// - STEPS: Controls the length of pb_task
+// - DOWNLOAD_URL: The file that gets downloaded by every task
 
 #[tokio::main]
 async fn main() {
     // adjust these constants to change program behavior
     const ITEMS: u64 = 10;
     const MAX_CONCURRENT: usize = 3;
-    const STEPS: u64 = 100;
+    const DOWNLOAD_URL: &str = "https://speed.hetzner.de/100MB.bin";
 
     println!(
         "\n Downloading {} files with max {} concurrent connections\n",
         ITEMS, MAX_CONCURRENT
     );
-    // set a style for all progress bar
+    // set a style for the main progress bar, tracking number of finished downloads
+    let pb_main_style = ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:50.cyan/blue} {pos:>7}/{len:7} {msg} ",
+    )
+    .unwrap()
+    .progress_chars("##-");
+
+    // set a style for each per-file progress bar, tracking bytes received
     // a list of template keys can be found here:
     // https://docs.rs/indicatif/latest/indicatif/index.html#templates
-    let pb_style = ProgressStyle::with_template(
-        "[{elapsed_precise}] {bar:50.cyan/blue} {pos:>7}/{len:7} {msg} ",
+    let pb_task_style = ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:50.cyan/blue} {bytes:>10}/{total_bytes:10} {bytes_per_sec:>12} {msg} ",
     )
     .unwrap()
     .progress_chars("##-");
 
-    // create a vec containing our "downloads" ... simple integer
-    // let ids: Vec<u64> = (0..ITEMS).into_iter().collect();
-    let files: Vec<Uuid> = (0..ITEMS).into_iter().map(|_| Uuid::new_v4()).collect();
+    // used in place of pb_task_style while a task is backing off after a failed attempt
+    let pb_retry_style = ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:50.red/blue} {bytes:>10}/{total_bytes:10} {bytes_per_sec:>12} {msg} ",
+    )
+    .unwrap()
+    .progress_chars("##-");
+
+    let task_styles = TaskStyles {
+        normal: pb_task_style.clone(),
+        retry: pb_retry_style,
+    };
+
+    // create a vec containing our "downloads" ... one uuid per file
+    let files: Vec<Uuid> = (0..ITEMS).map(|_| Uuid::new_v4()).collect();
 
     // create a struct to manage our progress bars -> indicatif::MultiProgress
     let multi_pg = MultiProgress::new();
 
     // create a progress bar to track overall status
     let pb_main = multi_pg.add(ProgressBar::new(ITEMS));
-    pb_main.set_style(pb_style.clone());
+    pb_main.set_style(pb_main_style);
     pb_main.set_message("total  ");
 
     // Make the main progress bar render immediately rather than waiting for the
     // first task to finish.
     pb_main.tick();
 
+    // a spinner below pb_main showing aggregate throughput and ETA, refreshed
+    // independently of task completions by the interval ticker further down
+    let pb_stats = multi_pg.add(ProgressBar::new_spinner());
+    pb_stats.enable_steady_tick(Duration::from_millis(100));
+
+    // baseline for the stats ticker below, captured here (before any task is
+    // spawned) so the first reported rate is measured from t=0, not from
+    // whenever the spawn loop happens to finish
+    let mut stats_interval = tokio::time::interval(Duration::from_millis(250));
+    let mut last_bytes = 0u64;
+    let mut last_tick = Instant::now();
+
     // tokio::task::JoinSet
     // setup the JoinSet to manage the join handles for our futures
     let mut set = JoinSet::new();
 
-    let mut last_item = false;
+    // a semaphore with MAX_CONCURRENT permits keeps exactly that many downloads
+    // in flight: every task is spawned immediately, but each acquires its own
+    // permit before starting its transfer, so only MAX_CONCURRENT run at once
+    // while the main task stays free to drive the drain + stats loop from t=0
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT));
+
+    // shared token so Ctrl-C can tell every in-flight task to abort cleanly
+    let token = CancellationToken::new();
 
-    // iterate over our downloads vec and
-    // spawn a background task for each download (do_stuff)
-    // Does not spawn more tasks than MAX_CONCURRENT "allows"
-    for (index, uuid) in files.iter().enumerate() {
-        if index == files.len() - 1 {
-            last_item = true;
+    // running total of bytes received across every task, read by the stats ticker;
+    // a failed attempt's bytes are taken back out of it
+    let total_bytes = Arc::new(AtomicU64::new(0));
+
+    // cancel the shared token as soon as Ctrl-C is hit. Spawned before the spawn
+    // loop below; the handler is installed once this task is first polled, and from
+    // then on a signal is caught by us, not the default SIGINT handler
+    let signal_token = token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            signal_token.cancel();
         }
+    });
 
+    // spawn a background task for each download (do_stuff) immediately; each task
+    // throttles itself by acquiring a semaphore permit before it starts transferring
+    for (index, uuid) in files.into_iter().enumerate() {
         // create a progress bar for each download and set the style
         // using insert_before() so that pb_main stays below the other progress bars
-        let pb_task = multi_pg.insert_before(&pb_main, ProgressBar::new(STEPS));
-        pb_task.set_style(pb_style.clone());
+        let pb_task = multi_pg.insert_before(&pb_main, ProgressBar::new(0));
+        pb_task.set_style(pb_task_style.clone());
+
+        let task_semaphore = semaphore.clone();
+        let task_token = token.clone();
+        let task_total_bytes = total_bytes.clone();
+        let task_styles = task_styles.clone();
 
         // spawns a background task immediatly no matter if the future is awaited
         // https://docs.rs/tokio/latest/tokio/task/struct.JoinSet.html#method.spawn
-        set.spawn(do_stuff(*uuid, index, STEPS, pb_task));
-
-        // when limit is reached, wait until a running task finishes
-        // await the future (join_next().await) and get the execution result
-        // here result would be a download id(u64), as you can see in signature of do_stuff
-        while set.len() >= MAX_CONCURRENT || last_item {
-            match set.join_next().await {
-                Some(_res) => {
-                    // let foo = res.unwrap()
-                    /* do something with foo */
-                }
-                None => {
-                    break;
-                }
-            };
-            pb_main.inc(1);
+        set.spawn(async move {
+            let _permit = task_semaphore.acquire_owned().await.unwrap();
+            do_stuff(
+                uuid,
+                index,
+                DOWNLOAD_URL,
+                pb_task,
+                task_styles,
+                task_token,
+                task_total_bytes,
+            )
+            .await
+        });
+    }
+
+    // drain the JoinSet as a Stream: filter_map classifies each result as completed,
+    // permanently failed, or aborted (cancelled, or the task panicked), then for_each
+    // tallies it and advances pb_main
+    let completed = Arc::new(AtomicU64::new(0));
+    let failed = Arc::new(AtomicU64::new(0));
+    let aborted = Arc::new(AtomicU64::new(0));
+
+    #[derive(Clone, Copy)]
+    enum Outcome {
+        Completed,
+        Failed,
+        Aborted,
+    }
+
+    let drain = JoinSetStream::new(set)
+        .filter_map(|res| async move {
+            match res {
+                Ok(Some(Ok(_uuid))) => Some(Outcome::Completed),
+                Ok(Some(Err(_))) => Some(Outcome::Failed),
+                Ok(None) | Err(_) => Some(Outcome::Aborted),
+            }
+        })
+        .for_each(|outcome| {
+            let completed = completed.clone();
+            let failed = failed.clone();
+            let aborted = aborted.clone();
+            let pb_main = pb_main.clone();
+            async move {
+                match outcome {
+                    Outcome::Completed => completed.fetch_add(1, Ordering::Relaxed),
+                    Outcome::Failed => failed.fetch_add(1, Ordering::Relaxed),
+                    Outcome::Aborted => aborted.fetch_add(1, Ordering::Relaxed),
+                };
+                pb_main.inc(1);
+            }
+        });
+    tokio::pin!(drain);
+
+    // tick the stats bar on its own cadence, racing it against the drain future
+    // so aggregate throughput keeps moving even while no individual task finishes
+    loop {
+        tokio::select! {
+            _ = stats_interval.tick() => {
+                let now = Instant::now();
+                let current_bytes = total_bytes.load(Ordering::Relaxed);
+                let elapsed = now.duration_since(last_tick).as_secs_f64();
+                let rate = if elapsed > 0.0 {
+                    // saturating: a rolled-back failed attempt can lower the counter
+                    (current_bytes.saturating_sub(last_bytes) as f64 / elapsed) as u64
+                } else {
+                    0
+                };
+                last_bytes = current_bytes;
+                last_tick = now;
+                // pb_main's own eta (derived from items/sec) doubles as the global
+                // ETA for the whole batch, since every task downloads one file
+                pb_stats.set_message(format!(
+                    "{} downloaded, {}/s, eta {}",
+                    HumanBytes(current_bytes),
+                    HumanBytes(rate),
+                    HumanDuration(pb_main.eta()),
+                ));
+            }
+            _ = &mut drain => break,
         }
     }
-    pb_main.finish_with_message("All Downloads finished");
+
+    pb_stats.finish_and_clear();
+    pb_main.finish_with_message(format!(
+        "finished: {} completed, {} failed, {} aborted",
+        completed.load(Ordering::Relaxed),
+        failed.load(Ordering::Relaxed),
+        aborted.load(Ordering::Relaxed)
+    ));
 }
 
-async fn do_stuff(uuid: Uuid, index: usize, steps: u64, pb_task: ProgressBar) -> Uuid {
+// how many times a failed download is retried before giving up for good
+const MAX_RETRIES: u32 = 4;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+// Returns `None` when the download was cancelled via `token` (e.g. the user hit
+// Ctrl-C), otherwise `Some(Ok(uuid))` on success or `Some(Err(_))` once retries
+// are exhausted or an error is not retryable.
+async fn do_stuff(
+    uuid: Uuid,
+    index: usize,
+    url: &str,
+    pb_task: ProgressBar,
+    task_styles: TaskStyles,
+    token: CancellationToken,
+    total_bytes: Arc<AtomicU64>,
+) -> Option<Result<Uuid, DownloadError>> {
     // set a {msg} for the task progress bar, appears right next to the progress indicator
     pb_task.set_message(format!("RECV file # {} with uuid:{}", index, uuid));
 
-    // we create a loop with sleep to simulate download progress
-    // using rand with a range (in millisecs) to create "download duration"
-    // calculate "tick size" for each progress bar step "download duration" / "# of steps in pb_task"
-    let num = rand::thread_rng().gen_range(steps..=5000);
-    let tick = num / steps;
+    for attempt in 0..=MAX_RETRIES {
+        pb_task.set_position(0);
+
+        let attempt_result = tokio::select! {
+            result = try_download(uuid, url, &pb_task, &total_bytes) => result,
+            _ = token.cancelled() => {
+                pb_task.abandon_with_message("cancelled");
+                return None;
+            }
+        };
+
+        if attempt_result.is_err() {
+            // roll back whatever this attempt added to the shared counter (pb_task's
+            // position, reset above, holds exactly the bytes this attempt read): the
+            // next attempt recreates the file from scratch, so counting them toward the
+            // aggregate "downloaded" total would double-count them on a later retry
+            // (or overcount them if the download is abandoned for good)
+            total_bytes.fetch_sub(pb_task.position(), Ordering::Relaxed);
+        }
+
+        match attempt_result {
+            Ok(()) => {
+                // finish the task progress bar
+                // pb_task could also be returned from this function
+                // and then used in the drain stream where the result is finally consumed
+                pb_task.finish_with_message(format!("DONE file # {} with uuid:{}", index, uuid));
+                return Some(Ok(uuid));
+            }
+            Err(err) if attempt < MAX_RETRIES && err.is_retryable() => {
+                let delay = RETRY_BASE_DELAY
+                    .saturating_mul(1 << attempt)
+                    .min(RETRY_MAX_DELAY)
+                    + Duration::from_millis(rand::thread_rng().gen_range(0..250));
+
+                pb_task.set_style(task_styles.retry.clone());
+                pb_task.set_message(format!(
+                    "retry {}/{} in {:.1}s: {}",
+                    attempt + 1,
+                    MAX_RETRIES,
+                    delay.as_secs_f64(),
+                    err,
+                ));
 
-    // heavy downloading ...
-    for _ in 0..steps {
-        sleep(Duration::from_millis(tick)).await;
-        pb_task.inc(1);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = token.cancelled() => {
+                        pb_task.abandon_with_message("cancelled");
+                        return None;
+                    }
+                }
+                pb_task.set_style(task_styles.normal.clone());
+            }
+            Err(err) => {
+                pb_task.abandon_with_message(format!("failed: {}", err));
+                return Some(Err(err));
+            }
+        }
     }
-    // finish the task progress bar
-    // pb_task could also be returned from this function
-    // and then used in the while loop the future is finally awaited
-    pb_task.finish_with_message(format!("DONE file # {} with uuid:{}", index, uuid));
-    uuid
+
+    unreachable!("loop always returns within MAX_RETRIES + 1 attempts")
+}
+
+// Performs a single download attempt: issues the request, streams the body to
+// disk, and feeds both `pb_task` and the aggregate `total_bytes` counter.
+async fn try_download(
+    uuid: Uuid,
+    url: &str,
+    pb_task: &ProgressBar,
+    total_bytes: &Arc<AtomicU64>,
+) -> Result<(), DownloadError> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let total_size = response.content_length().unwrap_or(0);
+    pb_task.set_length(total_size);
+
+    // wrap the response body in an AsyncRead and hand it to indicatif, which then
+    // increments pb_task for every byte read on its own - no manual inc() needed;
+    // CountingReader additionally feeds the shared aggregate counter
+    let byte_stream = response.bytes_stream().map_err(io::Error::other);
+    let mut reader = CountingReader {
+        inner: pb_task.wrap_async_read(StreamReader::new(byte_stream)),
+        total_bytes: total_bytes.clone(),
+    };
+
+    let mut file = tokio::fs::File::create(format!("{}.bin", uuid)).await?;
+    io::copy(&mut reader, &mut file).await?;
+    Ok(())
 }